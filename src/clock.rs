@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Time source used by `Chip8::update_timers`.
+/// It's owned by the emulator, so it has to be `Send + Sync` to keep the emulator shareable between threads.
+pub trait Clock: Send + Sync {
+    /// Time passed since some fixed point, which never changes for one clock.
+    fn now(&self) -> Duration;
+}
+
+/// Real time, measured from the moment the clock was created.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Clock that only moves when advanced manually. Clones share the same time,
+/// so one can be given to the emulator and another kept to drive it.
+#[derive(Clone, Default)]
+pub struct FakeClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
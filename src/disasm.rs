@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Decoded instruction. Decoding follows the interpreter, so anything it
+/// would reject with "Invalid opcode" doesn't decode. The only exception is
+/// XO-CHIP's 4-byte F000 NNNN, which is decoded by `decode_at` for listings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Sys(u16),        // 0nnn
+    Scd(u8),         // 00Cn
+    Cls,             // 00E0
+    Ret,             // 00EE
+    Scr,             // 00FB
+    Scl,             // 00FC
+    Exit,            // 00FD
+    Low,             // 00FE
+    High,            // 00FF
+    Jp(u16),         // 1nnn
+    Call(u16),       // 2nnn
+    Se(u8, u8),      // 3xkk
+    Sne(u8, u8),     // 4xkk
+    SeReg(u8, u8),   // 5xy0
+    Ld(u8, u8),      // 6xkk
+    Add(u8, u8),     // 7xkk
+    LdReg(u8, u8),   // 8xy0
+    Or(u8, u8),      // 8xy1
+    And(u8, u8),     // 8xy2
+    Xor(u8, u8),     // 8xy3
+    AddReg(u8, u8),  // 8xy4
+    Sub(u8, u8),     // 8xy5
+    Shr(u8, u8),     // 8xy6
+    Subn(u8, u8),    // 8xy7
+    Shl(u8, u8),     // 8xyE
+    SneReg(u8, u8),  // 9xy0
+    LdI(u16),        // Annn
+    JpV0(u16),       // Bnnn
+    Rnd(u8, u8),     // Cxkk
+    Drw(u8, u8, u8), // Dxyn
+    Skp(u8),         // Ex9E
+    Sknp(u8),        // ExA1
+    LdVxDt(u8),      // Fx07
+    LdVxK(u8),       // Fx0A
+    LdDtVx(u8),      // Fx15
+    LdStVx(u8),      // Fx18
+    AddIVx(u8),      // Fx1E
+    LdFVx(u8),       // Fx29
+    LdHfVx(u8),      // Fx30
+    LdBVx(u8),       // Fx33
+    LdIVx(u8),       // Fx55
+    LdVxI(u8),       // Fx65
+    LdILong(u16),    // F000 nnnn
+}
+
+pub fn decode(opcode: u16) -> Option<Opcode> {
+    use Opcode::*;
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let kk = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+
+    let op = match (opcode & 0xF000) >> 12 {
+        0x0 => match opcode {
+            0x00C0..=0x00CF => Scd(n),
+            0x00E0 => Cls,
+            0x00EE => Ret,
+            0x00FB => Scr,
+            0x00FC => Scl,
+            0x00FD => Exit,
+            0x00FE => Low,
+            0x00FF => High,
+            _ => Sys(nnn),
+        },
+        0x1 => Jp(nnn),
+        0x2 => Call(nnn),
+        0x3 => Se(x, kk),
+        0x4 => Sne(x, kk),
+        0x5 => SeReg(x, y),
+        0x6 => Ld(x, kk),
+        0x7 => Add(x, kk),
+        0x8 => match n {
+            0x0 => LdReg(x, y),
+            0x1 => Or(x, y),
+            0x2 => And(x, y),
+            0x3 => Xor(x, y),
+            0x4 => AddReg(x, y),
+            0x5 => Sub(x, y),
+            0x6 => Shr(x, y),
+            0x7 => Subn(x, y),
+            0xE => Shl(x, y),
+            _ => return None,
+        },
+        0x9 => SneReg(x, y),
+        0xA => LdI(nnn),
+        0xB => JpV0(nnn),
+        0xC => Rnd(x, kk),
+        0xD => Drw(x, y, n),
+        0xE => match kk {
+            0x9E => Skp(x),
+            0xA1 => Sknp(x),
+            _ => return None,
+        },
+        0xF => match kk {
+            0x07 => LdVxDt(x),
+            0x0A => LdVxK(x),
+            0x15 => LdDtVx(x),
+            0x18 => LdStVx(x),
+            0x1E => AddIVx(x),
+            0x29 => LdFVx(x),
+            0x30 => LdHfVx(x),
+            0x33 => LdBVx(x),
+            0x55 => LdIVx(x),
+            0x65 => LdVxI(x),
+            _ => return None,
+        },
+        _ => unreachable!(),
+    };
+    Some(op)
+}
+
+/// Decodes the instruction at `addr`, returning it along with its length in bytes.
+/// Unlike `decode`, this understands F000 NNNN, as long as `mem` contains its second word.
+pub fn decode_at(mem: &[u8], addr: usize) -> Option<(Opcode, usize)> {
+    let word = |a: usize| (mem[a] as u16) << 8 | mem[a + 1] as u16;
+    if addr + 1 >= mem.len() {
+        return None;
+    }
+    match word(addr) {
+        0xF000 if addr + 3 < mem.len() => Some((Opcode::LdILong(word(addr + 2)), 4)),
+        0xF000 => None,
+        opcode => decode(opcode).map(|op| (op, 2)),
+    }
+}
+
+impl Opcode {
+    /// Length of the instruction in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Opcode::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+    /// Like `to_string`, but jump and call targets found in `symbols` are shown by name.
+    pub fn to_string_with_symbols(&self, symbols: &HashMap<usize, String>) -> String {
+        use Opcode::*;
+
+        let symbol = match *self {
+            Jp(nnn) | Call(nnn) | JpV0(nnn) => symbols.get(&(nnn as usize)),
+            _ => None,
+        };
+        match (*self, symbol) {
+            (Jp(_), Some(name)) => format!("JP {}", name),
+            (Call(_), Some(name)) => format!("CALL {}", name),
+            (JpV0(_), Some(name)) => format!("JP V0, {}", name),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Opcode::*;
+
+        match *self {
+            Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Scd(n) => write!(f, "SCD {}", n),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Se(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Sne(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Ld(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Add(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Rnd(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            LdILong(nnnn) => write!(f, "LD I, 0x{:04X}", nnnn),
+        }
+    }
+}
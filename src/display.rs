@@ -1,4 +1,6 @@
 use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 /// Expands 8-bit integer to 16-bit like this:
 /// 0b01010111 -> 0b0011001100111111
@@ -11,16 +13,41 @@ fn expand(n: u8) -> u16 {
     result | (result << 1)
 }
 
+/// Inverse of `expand`, but for a whole row: keeps every second bit.
+fn shrink(row: u128) -> u64 {
+    let mut result: u64 = 0;
+    for i in 0..64 {
+        result |= ((row >> (i * 2)) as u64 & 1) << i
+    }
+    result
+}
+
 #[inline(always)]
 pub fn get_px(d: &[u128; 64], x: usize, y: usize) -> bool {
     let (shifted, _) = d[y].overflowing_shr(127 - x as u32);
     (shifted & 1) == 1
 }
 
+/// How sprites are combined with what's already on the screen.
+/// Standard Chip-8 only uses Xor, the others are for experimental modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Sprite pixels toggle screen pixels. Collisions mean erased pixels.
+    #[default]
+    Xor,
+    /// Sprite pixels are lit on top of the screen. Nothing gets erased, but overlaps are still reported.
+    Or,
+    /// Within the sprite's area, only pixels lit in both the sprite and the screen stay lit.
+    /// Overlaps are reported, even though the pixels that get erased are the ones that didn't overlap.
+    And,
+}
+
 pub struct Display {
     d: Box<[u128; 64]>,
     hi_res: bool,
     dirty: bool,
+    blend_mode: BlendMode,
+    last_hash: Option<u64>, // Content hash at the last frame_changed_since_last call
 }
 
 impl Display {
@@ -29,9 +56,19 @@ impl Display {
             d: Box::new([0; 64]),
             hi_res: false,
             dirty: false,
+            blend_mode: BlendMode::Xor,
+            last_hash: None,
         }
     }
 
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
     pub fn hi_res_mode(&mut self) {
         self.hi_res = true
     }
@@ -63,51 +100,73 @@ impl Display {
 
     pub fn clear(&mut self) {
         self.dirty = true;
-        self.d = Box::new([0; 64])
+        *self.d = [0; 64]
     }
 
+    /// Returns whether the sprite overlapped any lit pixels, which is the collision flag.
+    /// Only in Xor mode does that mean some pixels got erased.
     pub fn write(&mut self, b: u8, mut x: usize, mut y: usize) -> bool {
-        let b = if !self.hi_res {
+        // The mask covers all pixels the sprite byte spans, including unset ones
+        let (b, mask) = if !self.hi_res {
             x *= 2;
             y *= 2;
-            expand(b)
+            (expand(b), 0xFFFF)
         } else {
-            (b as u16) << 8
+            ((b as u16) << 8, 0xFF00)
         };
 
         let x = x % 128;
         let y = y % 64;
 
-        let mut erased = false;
         self.dirty = true;
-        let mut b = (b as u128) << 112;
-        b = b.rotate_right(x as u32);
-
-        if b & self.d[y] != 0 {
-            erased = true
-        };
-        self.d[y] ^= b;
+        let b = ((b as u128) << 112).rotate_right(x as u32);
+        let mask = ((mask as u128) << 112).rotate_right(x as u32);
 
+        let mut erased = self.blend(y, b, mask);
         if !self.hi_res {
-            if b & self.d[y + 1] != 0 {
-                erased = true
-            };
-            self.d[y + 1] ^= b;
+            erased |= self.blend(y + 1, b, mask);
         }
 
         erased
     }
 
+    fn blend(&mut self, y: usize, b: u128, mask: u128) -> bool {
+        let overlap = b & self.d[y] != 0;
+        match self.blend_mode {
+            BlendMode::Xor => self.d[y] ^= b,
+            BlendMode::Or => self.d[y] |= b,
+            BlendMode::And => self.d[y] &= b | !mask,
+        }
+        overlap
+    }
+
     pub fn read(&mut self) -> &[u128; 64] {
         self.dirty = false;
         &self.d
     }
 
+    /// Like `read`, but leaves the dirty flag alone.
+    #[cfg(feature = "gif")]
+    pub(crate) fn buffer(&self) -> &[u128; 64] {
+        &self.d
+    }
+
     pub fn read_px(&mut self, x: usize, y: usize) -> bool {
         self.dirty = false;
         get_px(&self.d, x, y)
     }
 
+    /// Pixels of a logical row, leftmost pixel in the highest bit.
+    /// In low-res mode the row is de-doubled into the lower 64 bits.
+    /// Panics if `logical_y` is 32 or more in low-res mode, or 64 or more in hi-res mode.
+    pub fn row(&self, logical_y: usize) -> u128 {
+        if self.hi_res {
+            self.d[logical_y]
+        } else {
+            shrink(self.d[logical_y * 2]) as u128
+        }
+    }
+
     pub fn hi_res(&self) -> bool {
         self.hi_res
     }
@@ -115,6 +174,21 @@ impl Display {
     pub fn dirty(&self) -> bool {
         self.dirty
     }
+
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.d.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Compares the content with what it was at the previous call (always true on the first one).
+    /// Unlike the dirty flag, drawing something and erasing it again doesn't count as a change.
+    pub fn frame_changed_since_last(&mut self) -> bool {
+        let hash = self.content_hash();
+        let changed = self.last_hash != Some(hash);
+        self.last_hash = Some(hash);
+        changed
+    }
 }
 
 impl Default for Display {
@@ -158,3 +232,53 @@ pub const DEFAULT_FONT: [u8; 240] = [
     0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
     0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_returns_logical_row_bits() {
+        let mut d = Display::new();
+        d.write(0b1100_0001, 1, 5);
+        assert_eq!(d.row(5), 0b1100_0001 << 55);
+        assert_eq!(d.row(4), 0);
+
+        d.hi_res_mode();
+        d.write(0b1010_0000, 120, 5);
+        assert_eq!(d.row(5), 0b1010_0000);
+    }
+
+    #[test]
+    fn detects_content_changes_between_frames() {
+        let mut d = Display::new();
+        d.write(0xF0, 0, 0);
+        assert!(d.frame_changed_since_last());
+        assert!(!d.frame_changed_since_last());
+        // Drawing and erasing again leaves the content as it was
+        d.write(0xFF, 8, 8);
+        d.write(0xFF, 8, 8);
+        assert!(!d.frame_changed_since_last());
+        d.write(0x0F, 8, 8);
+        assert!(d.frame_changed_since_last());
+    }
+
+    #[test]
+    fn blend_modes() {
+        let mut d = Display::new();
+        d.write(0xF0, 0, 0);
+        assert!(d.write(0xF0, 0, 0));
+        assert_eq!(d.row(0), 0);
+
+        d.set_blend_mode(BlendMode::Or);
+        d.write(0xF0, 0, 0);
+        assert!(d.write(0xF0, 0, 0));
+        assert_eq!(d.row(0), 0xF0 << 56);
+
+        // And only touches pixels within the sprite's 8 columns
+        d.write(0xFF, 8, 0);
+        d.set_blend_mode(BlendMode::And);
+        d.write(0x0F, 8, 0);
+        assert_eq!(d.row(0), 0xF00F << 48);
+    }
+}
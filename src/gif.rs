@@ -0,0 +1,117 @@
+use crate::display::get_px;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Codes are written with a minimum code size of 7, making each of them exactly one byte.
+// Clearing the table every 126 pixels keeps the code size from ever growing,
+// so no actual compression has to be done.
+const MIN_CODE_SIZE: u8 = 7;
+const CLEAR: u8 = 1 << MIN_CODE_SIZE;
+const END: u8 = CLEAR + 1;
+const CODES_PER_CLEAR: usize = 126;
+// Largest scale whose image width still fits in the u16 the format uses
+const MAX_SCALE: usize = u16::MAX as usize / 128;
+
+/// Collects display frames and writes them as an animated black and white GIF.
+pub struct GifRecorder {
+    path: PathBuf,
+    delay: u16, // In hundredths of a second
+    scale: usize,
+    frames: Vec<[u128; 64]>,
+}
+
+impl GifRecorder {
+    /// The frame delay is rounded to whole hundredths of a second and is at least 2,
+    /// because many viewers slow down frames with shorter delays to 10.
+    /// So anything above 50 fps plays at 50. The scale is clamped to 1..=511.
+    pub fn new<P: AsRef<Path>>(path: P, fps: u16, scale: usize) -> GifRecorder {
+        let fps = fps.max(1) as u32;
+        GifRecorder {
+            path: path.as_ref().to_path_buf(),
+            delay: ((100 + fps / 2) / fps).max(2) as u16,
+            scale: scale.clamp(1, MAX_SCALE),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn capture(&mut self, d: &[u128; 64]) {
+        self.frames.push(*d)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn finish(&self) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(&self.path)?);
+        let width = (128 * self.scale) as u16;
+        let height = (64 * self.scale) as u16;
+
+        w.write_all(b"GIF89a")?;
+        w.write_all(&width.to_le_bytes())?;
+        w.write_all(&height.to_le_bytes())?;
+        // Global color table of two colors, background 0, square pixels
+        w.write_all(&[0xF0, 0, 0])?;
+        w.write_all(&[0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF])?;
+        // Loop forever
+        w.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        for frame in &self.frames {
+            w.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+            w.write_all(&self.delay.to_le_bytes())?;
+            w.write_all(&[0x00, 0x00])?;
+
+            w.write_all(&[0x2C, 0, 0, 0, 0])?;
+            w.write_all(&width.to_le_bytes())?;
+            w.write_all(&height.to_le_bytes())?;
+            w.write_all(&[0x00, MIN_CODE_SIZE])?;
+
+            let data = self.encode(frame);
+            for block in data.chunks(255) {
+                w.write_all(&[block.len() as u8])?;
+                w.write_all(block)?;
+            }
+            w.write_all(&[0x00])?;
+        }
+
+        w.write_all(&[0x3B])?;
+        w.flush()
+    }
+
+    fn encode(&self, frame: &[u128; 64]) -> Vec<u8> {
+        let pixels = 128 * 64 * self.scale * self.scale;
+        let mut data = Vec::with_capacity(pixels + pixels / CODES_PER_CLEAR + 2);
+        let width = 128 * self.scale;
+        for i in 0..pixels {
+            if i % CODES_PER_CLEAR == 0 {
+                data.push(CLEAR)
+            }
+            let (x, y) = (i % width / self.scale, i / width / self.scale);
+            data.push(get_px(frame, x, y) as u8)
+        }
+        data.push(END);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_is_rounded_and_at_least_two() {
+        let delay = |fps| GifRecorder::new("", fps, 1).delay;
+        assert_eq!(delay(60), 2);
+        assert_eq!(delay(30), 3);
+        assert_eq!(delay(25), 4);
+        assert_eq!(delay(7), 14);
+        assert_eq!(delay(0), 100);
+    }
+
+    #[test]
+    fn scale_is_clamped() {
+        assert_eq!(GifRecorder::new("", 30, 0).scale, 1);
+        assert_eq!(GifRecorder::new("", 30, 1000).scale, 511);
+    }
+}
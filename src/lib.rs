@@ -1,20 +1,105 @@
+pub mod clock;
+pub mod disasm;
 pub mod display;
+#[cfg(feature = "gif")]
+pub mod gif;
 
 use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+use std::fmt::Write;
+#[cfg(not(feature = "safe"))]
 use std::hint::unreachable_unchecked;
+use std::ops::Range;
+use std::time::Duration;
+
+// Under VIP timing DXYN costs DRAW_BASE_COST plus DRAW_ROW_COST per sprite row, in units of
+// an ordinary instruction. These are rough ratios, not the COSMAC VIP's real cycle counts:
+// they only make drawing, and taller sprites especially, slower than other instructions.
+pub const DRAW_BASE_COST: u64 = 2;
+pub const DRAW_ROW_COST: u64 = 1;
 
 #[inline(always)]
 pub fn get_opcode(mem: &[u8; 4096], addr: usize) -> u16 {
     (mem[addr] as u16) << 8 | mem[addr + 1] as u16
 }
 
+/// Extensions and features supported by this build of the emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Crate version the emulator was built from.
+    pub version: &'static str,
+    pub super_chip: bool,
+    pub xo_chip: bool,
+    pub planes: bool,
+    pub audio_pattern: bool,
+    pub gif_recording: bool,
+    pub vip_timing: bool,
+    pub blend_modes: bool,
+}
+
+pub const fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        super_chip: true,
+        xo_chip: false,
+        planes: false,
+        audio_pattern: false,
+        gif_recording: cfg!(feature = "gif"),
+        vip_timing: true,
+        blend_modes: true,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomReport {
+    pub size: usize,
+    /// Whether the program fits in memory when loaded at 0x200.
+    pub fits: bool,
+    /// Addresses (as loaded at 0x200) of words the interpreter would reject as invalid opcodes,
+    /// including XO-CHIP's F000. Sprite data usually ends up here too, so this isn't necessarily an error.
+    pub undecodable: Vec<usize>,
+}
+
+impl RomReport {
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+/// Statically checks a ROM without loading or running it.
+pub fn validate_rom(program: &[u8]) -> RomReport {
+    // Walking word by word, like the interpreter does, and ignoring a trailing odd byte
+    let undecodable = program
+        .chunks_exact(2)
+        .enumerate()
+        .filter(|(_, word)| disasm::decode((word[0] as u16) << 8 | word[1] as u16).is_none())
+        .map(|(i, _)| 0x200 + 2 * i)
+        .collect();
+    RomReport {
+        size: program.len(),
+        fits: 0x200 + program.len() <= 4096,
+        undecodable,
+    }
+}
+
+// Frontends share the emulator between threads, so every field has to stay Send + Sync
+fn _assert_send_sync() {
+    fn assert<T: Send + Sync>() {}
+    assert::<Chip8>()
+}
+
+/// Snapshot of the emulator's state.
+///
+/// `stack[..sp]` holds the return addresses, oldest first, so the top of the stack is
+/// `stack[sp - 1]` and `sp` is the stack depth (0 when empty, 16 when full).
+/// Before 0.3.0, `sp` was the index of the top entry instead.
 pub struct Chip8State {
     pub mem: Box<[u8; 4096]>,
     pub regs: [u8; 16],
     pub stack: [usize; 16],
     pub pc: usize, // Program counter
     pub i: usize,  // I-register
-    pub sp: usize, // Stack pointer
+    pub sp: usize, // Stack depth, see above
     pub sound_timer: u8,
     pub delay_timer: u8
 }
@@ -25,12 +110,26 @@ pub struct Chip8 {
     stack: [usize; 16],
     pc: usize, // Program counter
     i: usize,  // I-register
-    sp: usize, // Stack pointer
+    sp: usize, // Stack pointer, also the number of used stack slots
     sound_timer: u8,
     delay_timer: u8,
+    program_range: Range<usize>,
+    symbols: HashMap<usize, String>,
+    last_instruction_drew: bool,
+    key_timeouts: [usize; 16], // Frames left before an auto-released key goes up
+    tapped_key: Option<u8>,
+    vip_timing: bool,
+    load_store_quirk: bool, // FX55/FX65 advance I past the last register, like the COSMAC VIP
+    cycles: u64, // Machine cycles spent so far, see instruction_cost
+    clock: Box<dyn clock::Clock>,
+    timer_epoch: Duration, // Clock time update_timers counts ticks from
+    timer_ticks: u128,     // Ticks done by update_timers since timer_epoch
     pub display: display::Display,
     key_wait_handler: &'static (dyn Fn() -> u8 + Send + Sync + 'static),
     key_state_handler: &'static (dyn Fn(u8) -> bool + Send + Sync + 'static),
+    sound_tick_callback: Option<Box<dyn FnMut(u8) + Send + Sync>>,
+    #[cfg(feature = "gif")]
+    gif_recorder: Option<gif::GifRecorder>,
 }
 
 impl Chip8 {
@@ -48,9 +147,23 @@ impl Chip8 {
             sp: 0,
             sound_timer: 0,
             delay_timer: 0,
+            program_range: 0..0,
+            symbols: HashMap::new(),
+            last_instruction_drew: false,
+            key_timeouts: [0; 16],
+            tapped_key: None,
+            vip_timing: false,
+            load_store_quirk: false,
+            cycles: 0,
+            clock: Box::new(clock::SystemClock::new()),
+            timer_epoch: Duration::ZERO,
+            timer_ticks: 0,
             display: display::Display::new(),
             key_wait_handler,
             key_state_handler,
+            sound_tick_callback: None,
+            #[cfg(feature = "gif")]
+            gif_recorder: None,
         }
     }
 
@@ -76,14 +189,130 @@ impl Chip8 {
         self.key_state_handler = key_state_handler
     }
 
+    /// The callback is called from `timers_tick` with the decremented sound timer value
+    /// on every tick the sound timer was running, so the last call receives 0.
+    pub fn set_sound_tick_callback(&mut self, cb: Box<dyn FnMut(u8) + Send + Sync>) {
+        self.sound_tick_callback = Some(cb)
+    }
+
+    /// Starts capturing the display on every `timers_tick`, so `fps` should match the rate it's called at.
+    /// Each pixel of the 128x64 buffer becomes a `scale` by `scale` square in the GIF.
+    #[cfg(feature = "gif")]
+    pub fn start_recording_gif<P: AsRef<std::path::Path>>(&mut self, path: P, fps: u16, scale: usize) {
+        self.gif_recorder = Some(gif::GifRecorder::new(path, fps, scale))
+    }
+
+    /// Stops recording and writes the GIF. Does nothing if no recording was started.
+    #[cfg(feature = "gif")]
+    pub fn finish_recording(&mut self) -> std::io::Result<()> {
+        match self.gif_recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Holds the key down for the next `frames` calls to `timers_tick`, in addition to
+    /// whatever the key state handler reports. For frontends that don't get key release events.
+    /// A key read by FX0A is released right away, so it only satisfies one FX0A.
+    pub fn set_key_with_duration(&mut self, key: u8, frames: usize) {
+        self.key_timeouts[key as usize & 0xF] = frames
+    }
+
+    /// Makes the key look pressed to the next EX9E, EXA1 or FX0A only. Any of these clears it,
+    /// even if it checked a different key.
+    pub fn tap_key(&mut self, key: u8) {
+        self.tapped_key = Some(key & 0xF)
+    }
+
+    fn check_key(&mut self, key: u8) -> bool {
+        let tapped = self.tapped_key.take() == Some(key & 0xF);
+        tapped || self.key_timeouts[key as usize & 0xF] > 0 || (self.key_state_handler)(key)
+    }
+
+    /// With VIP timing on, sprite drawing costs `DRAW_BASE_COST` plus `DRAW_ROW_COST` per sprite row
+    /// instead of a single cycle. It's only a rough approximation of the COSMAC VIP's slow drawing.
+    pub fn set_vip_timing(&mut self, on: bool) {
+        self.vip_timing = on
+    }
+
+    pub fn vip_timing(&self) -> bool {
+        self.vip_timing
+    }
+
+    /// Makes FX55/FX65 leave I at I + X + 1, as the original COSMAC VIP interpreter did.
+    /// Off by default, leaving I unchanged like SuperChip. Some older programs rely on it.
+    pub fn set_load_store_quirk(&mut self, on: bool) {
+        self.load_store_quirk = on
+    }
+
+    pub fn load_store_quirk(&self) -> bool {
+        self.load_store_quirk
+    }
+
+    /// Machine cycles the opcode takes. Every instruction costs one cycle, except DXYN under VIP timing.
+    pub fn instruction_cost(&self, opcode: u16) -> u64 {
+        if self.vip_timing && opcode & 0xF000 == 0xD000 {
+            let rows = match opcode & 0x000F {
+                0 if self.display.hi_res() => 16,
+                n => n as u64,
+            };
+            DRAW_BASE_COST + DRAW_ROW_COST * rows
+        } else {
+            1
+        }
+    }
+
+    /// Machine cycles executed since creation.
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// One line per configurable setting, for logs and bug reports.
+    /// Runtime state like the resolution or the loaded program isn't included.
+    pub fn config_summary(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "platform: Chip-8 with SuperChip extensions").unwrap();
+        writeln!(out, "vip_timing: {}", self.vip_timing()).unwrap();
+        writeln!(out, "load_store_quirk: {}", self.load_store_quirk()).unwrap();
+        writeln!(out, "blend_mode: {:?}", self.display.blend_mode()).unwrap();
+        writeln!(out, "safe: {}", cfg!(feature = "safe")).unwrap();
+        writeln!(out, "memory: {} bytes", self.mem.len()).unwrap();
+        out
+    }
+
     pub fn get_regs(&self) -> [u8; 16] {
         self.regs
     }
 
+    /// Same as `get_regs`, the counterpart of `restore_registers`.
+    pub fn dump_registers(&self) -> [u8; 16] {
+        self.regs
+    }
+
+    pub fn restore_registers(&mut self, regs: [u8; 16]) {
+        self.regs = regs
+    }
+
     pub fn get_i(&self) -> usize {
         self.i
     }
 
+    /// Up to `len` bytes of memory starting at I, cut short at the end of memory.
+    pub fn data_at_i(&self, len: usize) -> &[u8] {
+        let start = self.i.min(self.mem.len());
+        let end = self.i.saturating_add(len).min(self.mem.len());
+        &self.mem[start..end]
+    }
+
+    /// Writes the data to memory starting at I.
+    /// Panics if it doesn't fit before the end of memory.
+    pub fn set_data_at_i(&mut self, data: &[u8]) {
+        if self.i + data.len() > self.mem.len() {
+            panic!("Data doesn't fit in memory at I.")
+        }
+        self.mem[self.i..self.i + data.len()].copy_from_slice(data)
+    }
+
     pub fn get_sound_timer(&self) -> u8 {
         self.sound_timer
     }
@@ -108,6 +337,86 @@ impl Chip8 {
         self.pc
     }
 
+    /// Return addresses currently on the stack, oldest first.
+    pub fn get_stack(&self) -> &[usize] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.sp
+    }
+
+    /// Whether the last executed instruction drew, cleared or scrolled the screen.
+    pub fn last_instruction_drew(&self) -> bool {
+        self.last_instruction_drew
+    }
+
+    /// Memory range occupied by the last loaded program.
+    pub fn program_range(&self) -> Range<usize> {
+        self.program_range.clone()
+    }
+
+    /// Symbol table (address to name) used by `disassemble_program` to annotate jumps and calls.
+    pub fn set_symbols(&mut self, symbols: HashMap<usize, String>) {
+        self.symbols = symbols
+    }
+
+    /// Decodes the loaded program from its start, pairing each instruction with its address.
+    /// Words that don't decode (usually sprite data) are dropped, leaving a gap in the addresses.
+    pub fn decoded_program(&self) -> Vec<(usize, disasm::Opcode)> {
+        let mem = &self.mem[..self.program_range.end];
+        let mut program = Vec::new();
+        let mut addr = self.program_range.start;
+        while addr + 1 < mem.len() {
+            match disasm::decode_at(mem, addr) {
+                Some((op, len)) => {
+                    program.push((addr, op));
+                    addr += len
+                }
+                None => addr += 2,
+            }
+        }
+        program
+    }
+
+    /// Lists the loaded program as `ADDR: OPCODE  MNEMONIC` lines.
+    /// Words that don't decode (usually sprite data) are shown as `DB`.
+    pub fn disassemble_program(&self) -> String {
+        let mut out = String::new();
+        let mut addr = self.program_range.start;
+        for (at, op) in self.decoded_program() {
+            self.list_data(&mut out, addr..at);
+            let opcode = self.get_opcode(at);
+            if op.size() == 4 {
+                let nnnn = self.get_opcode(at + 2);
+                writeln!(out, "{:04X}: {:04X} {:04X}  {}", at, opcode, nnnn, op).unwrap();
+            } else {
+                let mnemonic = op.to_string_with_symbols(&self.symbols);
+                writeln!(out, "{:04X}: {:04X}  {}", at, opcode, mnemonic).unwrap();
+            }
+            addr = at + op.size();
+        }
+        self.list_data(&mut out, addr..self.program_range.end);
+        out
+    }
+
+    fn list_data(&self, out: &mut String, range: Range<usize>) {
+        for addr in range.clone().step_by(2) {
+            if addr + 1 == range.end {
+                let b = self.mem[addr];
+                writeln!(out, "{:04X}: {:02X}    DB 0x{:02X}", addr, b, b).unwrap();
+            } else {
+                let (hi, lo) = (self.mem[addr], self.mem[addr + 1]);
+                writeln!(
+                    out,
+                    "{:04X}: {:02X}{:02X}  DB 0x{:02X}, 0x{:02X}",
+                    addr, hi, lo, hi, lo
+                )
+                .unwrap();
+            }
+        }
+    }
+
     /// The at parameter should almost always be 0x200. It's here for compatability with ETI 660 programs (starting with 0x600).
     /// Panics if at is less than 240, where the default font lies.
     pub fn load(&mut self, at: usize, program: &[u8], font: Option<[u8; 240]>) {
@@ -125,29 +434,83 @@ impl Chip8 {
             self.mem[i] = *c
         }
         self.pc = at;
+        self.program_range = at..at + program.len();
     }
 
-    fn stack_push(&mut self, v: usize) {
+    fn stack_push(&mut self, v: usize) -> Result<(), &'static str> {
+        if self.sp == self.stack.len() {
+            return Err("Stack overflow");
+        }
+        self.stack[self.sp] = v;
         self.sp += 1;
-        self.stack[self.sp] = v
+        Ok(())
     }
 
-    fn stack_pop(&mut self) -> usize {
+    fn stack_pop(&mut self) -> Result<usize, &'static str> {
+        if self.sp == 0 {
+            return Err("Stack underflow");
+        }
         self.sp -= 1;
-        self.stack[self.sp + 1]
+        Ok(self.stack[self.sp])
     }
 
     pub fn timers_tick(&mut self) {
+        for t in self.key_timeouts.iter_mut() {
+            *t = t.saturating_sub(1)
+        }
         if self.delay_timer > 0 {
             self.delay_timer -= 1
         }
         if self.sound_timer > 0 {
-            self.sound_timer -= 1
+            self.sound_timer -= 1;
+            if let Some(cb) = &mut self.sound_tick_callback {
+                cb(self.sound_timer)
+            }
+        }
+        #[cfg(feature = "gif")]
+        if let Some(recorder) = &mut self.gif_recorder {
+            recorder.capture(self.display.buffer())
+        }
+    }
+
+    /// Runs `frames` frames of `ipf` instructions each, ticking the timers after every frame,
+    /// without any pacing. Stops at the first error, including the program exiting.
+    pub fn run_turbo(&mut self, frames: usize, ipf: usize) -> Result<(), &'static str> {
+        for _ in 0..frames {
+            for _ in 0..ipf {
+                self.cpu_tick()?
+            }
+            self.timers_tick()
+        }
+        Ok(())
+    }
+
+    /// Replaces the clock used by `update_timers`, which then counts time from now on.
+    pub fn set_clock(&mut self, clock: Box<dyn clock::Clock>) {
+        self.timer_epoch = clock.now();
+        self.timer_ticks = 0;
+        self.clock = clock
+    }
+
+    /// Calls `timers_tick` as many times as needed to keep the timers at 60 Hz
+    /// of clock time since creation or the last `set_clock`. Returns the number of ticks done.
+    /// After a clock jump, at most a second's worth of ticks is done and the rest is skipped.
+    pub fn update_timers(&mut self) -> u32 {
+        let elapsed = self.clock.now().saturating_sub(self.timer_epoch);
+        let due = elapsed.as_nanos() * 60 / 1_000_000_000;
+        let ticks = due.saturating_sub(self.timer_ticks).min(60) as u32;
+        for _ in 0..ticks {
+            self.timers_tick()
         }
+        self.timer_ticks = due;
+        ticks
     }
 
     pub fn cpu_tick(&mut self) -> Result<(), &'static str> {
-        self.run_opcode((self.mem[self.pc] as u16) << 8 | self.mem[self.pc + 1] as u16)
+        self.last_instruction_drew = false;
+        let opcode = self.get_opcode(self.pc);
+        self.cycles += self.instruction_cost(opcode);
+        self.run_opcode(opcode)
     }
 
     fn run_opcode(&mut self, opcode: u16) -> Result<(), &'static str> {
@@ -161,11 +524,23 @@ impl Chip8 {
         match (opcode & 0xF000) >> 12 {
             // Instructions that mess with the program counter are returning after that so it wouldn't be incremented after.
             0x0 => match opcode {
-                0x00C0..=0x00CF => self.display.scroll_down(n() as u32),
-                0x00E0 => self.display.clear(),
-                0x00EE => self.pc = self.stack_pop(),
-                0x00FB => self.display.scroll_side(4),
-                0x00FC => self.display.scroll_side(-4),
+                0x00C0..=0x00CF => {
+                    self.display.scroll_down(n() as u32);
+                    self.last_instruction_drew = true
+                }
+                0x00E0 => {
+                    self.display.clear();
+                    self.last_instruction_drew = true
+                }
+                0x00EE => self.pc = self.stack_pop()?,
+                0x00FB => {
+                    self.display.scroll_side(4);
+                    self.last_instruction_drew = true
+                }
+                0x00FC => {
+                    self.display.scroll_side(-4);
+                    self.last_instruction_drew = true
+                }
                 0x00FD => return Err("Program exited"),
                 0x00FE => self.display.low_res_mode(),
                 0x00FF => self.display.hi_res_mode(),
@@ -176,7 +551,7 @@ impl Chip8 {
                 return Ok(());
             }
             0x2 => {
-                self.stack_push(self.pc);
+                self.stack_push(self.pc)?;
                 self.pc = nnn() as usize;
                 return Ok(());
             }
@@ -193,7 +568,7 @@ impl Chip8 {
                 }
             }
             0x5 => {
-                if self.regs[x()] == self.regs[y()] as u8 {
+                if self.regs[x()] == self.regs[y()] {
                     self.pc += 4;
                     return Ok(());
                 }
@@ -236,7 +611,7 @@ impl Chip8 {
                 _ => return Err("Invalid opcode"),
             },
             0x9 => {
-                if self.regs[x()] != self.regs[y()] as u8 {
+                if self.regs[x()] != self.regs[y()] {
                     self.pc += 4;
                     return Ok(());
                 }
@@ -254,12 +629,12 @@ impl Chip8 {
                         erased |= self.display.write(
                             self.mem[self.i + j * 2],
                             self.regs[x()] as usize,
-                            self.regs[y()] as usize + j as usize,
+                            self.regs[y()] as usize + j,
                         );
                         erased |= self.display.write(
                             self.mem[self.i + j * 2 + 1],
                             self.regs[x()] as usize + 8,
-                            self.regs[y()] as usize + j as usize,
+                            self.regs[y()] as usize + j,
                         )
                     }
                 } else {
@@ -271,17 +646,18 @@ impl Chip8 {
                         )
                     }
                 }
-                self.regs[0xF] = erased as u8
+                self.regs[0xF] = erased as u8;
+                self.last_instruction_drew = true
             }
             0xE => match opcode & 0x00FF {
                 0x9E => {
-                    if (self.key_state_handler)(self.regs[x()]) {
+                    if self.check_key(self.regs[x()]) {
                         self.pc += 4;
                         return Ok(());
                     }
                 }
                 0xA1 => {
-                    if !(self.key_state_handler)(self.regs[x()]) {
+                    if !self.check_key(self.regs[x()]) {
                         self.pc += 4;
                         return Ok(());
                     }
@@ -290,7 +666,18 @@ impl Chip8 {
             },
             0xF => match opcode & 0x00FF {
                 0x07 => self.regs[x()] = self.delay_timer,
-                0x0A => self.regs[x()] = (self.key_wait_handler)(),
+                0x0A => {
+                    let held = self.key_timeouts.iter().position(|&t| t > 0);
+                    self.regs[x()] = match (self.tapped_key.take(), held) {
+                        (Some(key), _) => key,
+                        (None, Some(key)) => {
+                            // Counts as the key's release, so the next FX0A waits for a new press
+                            self.key_timeouts[key] = 0;
+                            key as u8
+                        }
+                        (None, None) => (self.key_wait_handler)(),
+                    }
+                }
                 0x15 => self.delay_timer = self.regs[x()],
                 0x18 => self.sound_timer = self.regs[x()],
                 0x1E => {
@@ -305,21 +692,448 @@ impl Chip8 {
                     self.mem[self.i + 1] = vx % 100 / 10;
                     self.mem[self.i + 2] = vx % 100 % 10;
                 }
+                // FX55/FX65 leave I unchanged (SuperChip behaviour) unless the load/store quirk is on,
+                // so without it a store followed by a load with the same X always round-trips.
                 0x55 => {
                     for j in 0..=x() {
                         self.mem[self.i + j] = self.regs[j]
                     }
+                    if self.load_store_quirk {
+                        self.i += x() + 1
+                    }
                 }
                 0x65 => {
                     for j in 0..=x() {
                         self.regs[j] = self.mem[self.i + j]
                     }
+                    if self.load_store_quirk {
+                        self.i += x() + 1
+                    }
                 }
                 _ => return Err("Invalid opcode"),
             },
+            #[cfg(feature = "safe")]
+            _ => unreachable!(),
+            #[cfg(not(feature = "safe"))]
             _ => unsafe { unreachable_unchecked() },
         }
         self.pc += 2;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip8(program: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new::<(), ()>(&|| 0, &|_| false);
+        chip8.load(0x200, program, None);
+        chip8
+    }
+
+    #[test]
+    fn capabilities_match_implemented_features() {
+        let caps = capabilities();
+        let version: Vec<_> = caps.version.split('.').collect();
+        assert_eq!(version.len(), 3);
+        assert!(version.iter().all(|part| part.parse::<u32>().is_ok()));
+        assert_eq!(caps.gif_recording, cfg!(feature = "gif"));
+
+        // Every supported feature has to be reachable, every unsupported one has to be rejected
+        let mut chip8 = chip8(&[0x00, 0xFF, 0xF0, 0x30, 0xF0, 0x00]);
+        assert!(caps.super_chip);
+        chip8.cpu_tick().unwrap();
+        chip8.cpu_tick().unwrap();
+        assert!(chip8.display.hi_res());
+        assert_eq!(chip8.get_i(), 40);
+        assert!(!caps.xo_chip);
+        assert_eq!(chip8.cpu_tick(), Err("Invalid opcode"));
+
+        assert!(caps.vip_timing);
+        chip8.set_vip_timing(true);
+        assert!(chip8.instruction_cost(0xD01F) > chip8.instruction_cost(0xD011));
+
+        assert!(caps.blend_modes);
+        chip8.display.set_blend_mode(display::BlendMode::Or);
+        chip8.display.write(0x80, 0, 0);
+        chip8.display.write(0x80, 0, 0);
+        assert!(chip8.display.read_px(0, 0));
+
+        #[cfg(feature = "gif")]
+        {
+            assert!(caps.gif_recording);
+            chip8.start_recording_gif(std::env::temp_dir().join("unused.gif"), 30, 1);
+        }
+    }
+
+    #[test]
+    fn disassembles_whole_program() {
+        let chip8 = chip8(&[
+            0x00, 0xE0, 0xF0, 0x00, 0x03, 0x00, 0xD0, 0x15, 0x12, 0x00, 0xFF, 0xFF, 0xAB,
+        ]);
+        assert_eq!(
+            chip8.disassemble_program(),
+            "0200: 00E0  CLS\n\
+             0202: F000 0300  LD I, 0x0300\n\
+             0206: D015  DRW V0, V1, 5\n\
+             0208: 1200  JP 0x200\n\
+             020A: FFFF  DB 0xFF, 0xFF\n\
+             020C: AB    DB 0xAB\n"
+        );
+    }
+
+    #[test]
+    fn sound_tick_callback_gets_descending_values() {
+        use std::sync::{Arc, Mutex};
+
+        let values = Arc::new(Mutex::new(Vec::new()));
+        let mut chip8 = chip8(&[0x60, 0x03, 0xF0, 0x18]);
+        let v = values.clone();
+        chip8.set_sound_tick_callback(Box::new(move |t| v.lock().unwrap().push(t)));
+        chip8.cpu_tick().unwrap();
+        chip8.cpu_tick().unwrap();
+        for _ in 0..5 {
+            chip8.timers_tick()
+        }
+        assert_eq!(*values.lock().unwrap(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn validates_clean_rom() {
+        let report = validate_rom(&[0x00, 0xE0, 0xF0, 0x33, 0x00, 0xEE, 0x12, 0x00]);
+        assert_eq!(
+            report,
+            RomReport {
+                size: 8,
+                fits: true,
+                undecodable: vec![],
+            }
+        );
+        assert!(!report.is_empty());
+    }
+
+    #[test]
+    fn validates_garbage_rom() {
+        let report = validate_rom(&[0xFF, 0xFF, 0x00, 0xE0, 0xE0, 0x00, 0x80, 0x0F, 0xF0, 0x00, 0x03]);
+        assert_eq!(report.undecodable, vec![0x200, 0x204, 0x206, 0x208]);
+        assert!(!validate_rom(&[0; 4096 - 0x1FF]).fits);
+        assert!(validate_rom(&[]).is_empty());
+    }
+
+    #[test]
+    fn annotates_jumps_with_symbols() {
+        let mut chip8 = chip8(&[0x13, 0x00, 0x23, 0x00, 0xB3, 0x00, 0x14, 0x00]);
+        let mut symbols = HashMap::new();
+        symbols.insert(0x300, "draw_player".to_string());
+        chip8.set_symbols(symbols);
+        assert_eq!(
+            chip8.disassemble_program(),
+            "0200: 1300  JP draw_player\n\
+             0202: 2300  CALL draw_player\n\
+             0204: B300  JP V0, draw_player\n\
+             0206: 1400  JP 0x400\n"
+        );
+    }
+
+    #[test]
+    fn store_and_load_registers_round_trip() {
+        let regs: [u8; 16] = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ];
+        let mut chip8 = chip8(&[0xA3, 0x00, 0xFF, 0x55, 0xFF, 0x65]);
+        chip8.restore_registers(regs);
+        chip8.cpu_tick().unwrap();
+        chip8.cpu_tick().unwrap();
+        assert_eq!(chip8.get_i(), 0x300);
+        chip8.restore_registers([0; 16]);
+        chip8.cpu_tick().unwrap();
+        assert_eq!(chip8.get_regs(), regs);
+        assert_eq!(chip8.get_i(), 0x300);
+    }
+
+    #[test]
+    fn load_store_quirk_advances_i() {
+        let regs: [u8; 16] = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ];
+        // LD I, 0x300; LD [I], VF; LD I, 0x300; LD VF, [I]
+        let mut chip8 = chip8(&[0xA3, 0x00, 0xFF, 0x55, 0xA3, 0x00, 0xFF, 0x65]);
+        assert!(!chip8.load_store_quirk());
+        chip8.set_load_store_quirk(true);
+        assert!(chip8.load_store_quirk());
+        chip8.restore_registers(regs);
+        chip8.cpu_tick().unwrap();
+        chip8.cpu_tick().unwrap();
+        assert_eq!(chip8.get_i(), 0x310);
+        chip8.restore_registers([0; 16]);
+        chip8.cpu_tick().unwrap();
+        chip8.cpu_tick().unwrap();
+        assert_eq!(chip8.get_regs(), regs);
+        assert_eq!(chip8.get_i(), 0x310);
+    }
+
+    #[test]
+    fn turbo_runs_frames_and_ticks_timers() {
+        // LD V0, 0xFF; LD DT, V0; JP 0x204
+        let mut chip8 = chip8(&[0x60, 0xFF, 0xF0, 0x15, 0x12, 0x04]);
+        chip8.run_turbo(100, 10).unwrap();
+        assert_eq!(chip8.get_delay_timer(), 0xFF - 100);
+        assert_eq!(chip8.get_cycles(), 1000);
+        assert_eq!(chip8.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn turbo_stops_on_exit() {
+        let mut chip8 = chip8(&[0x00, 0xFD]);
+        assert_eq!(chip8.run_turbo(100, 10), Err("Program exited"));
+        assert_eq!(chip8.get_cycles(), 1);
+    }
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn records_gif_frames() {
+        let path = std::env::temp_dir().join(format!("chiprust-emu-{}.gif", std::process::id()));
+        // LD V0, 1; LD F, V0; DRW V0, V0, 5; ADD V0, 4; JP 0x202
+        let mut chip8 = chip8(&[0x60, 0x01, 0xF0, 0x29, 0xD0, 0x05, 0x70, 0x04, 0x12, 0x02]);
+        chip8.start_recording_gif(&path, 30, 2);
+        chip8.run_turbo(3, 4).unwrap();
+        chip8.finish_recording().unwrap();
+        let gif = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(&gif[6..10], &[0, 1, 128, 0]);
+        // Walk the blocks after the header and the 2-color global color table
+        let mut frames = 0;
+        let mut p = 13 + 6;
+        let skip_sub_blocks = |mut p: usize| {
+            while gif[p] != 0 {
+                p += gif[p] as usize + 1
+            }
+            p + 1
+        };
+        loop {
+            match gif[p] {
+                0x21 => p = skip_sub_blocks(p + 2),
+                0x2C => {
+                    frames += 1;
+                    p = skip_sub_blocks(p + 11)
+                }
+                0x3B => break,
+                b => panic!("Unexpected block {:#04X}", b),
+            }
+        }
+        assert_eq!(frames, 3);
+    }
+
+    #[test]
+    fn tracks_whether_last_instruction_drew() {
+        // DRW V0, V0, 5; LD V1, 0x12
+        let mut chip8 = chip8(&[0xD0, 0x05, 0x61, 0x12]);
+        chip8.cpu_tick().unwrap();
+        assert!(chip8.last_instruction_drew());
+        chip8.cpu_tick().unwrap();
+        assert!(!chip8.last_instruction_drew());
+    }
+
+    #[test]
+    fn duration_key_releases_after_its_frames() {
+        let mut chip8 = chip8(&[]);
+        chip8.set_key_with_duration(5, 3);
+        for _ in 0..3 {
+            assert!(chip8.check_key(5));
+            chip8.timers_tick();
+        }
+        assert!(!chip8.check_key(5));
+    }
+
+    #[test]
+    fn duration_key_satisfies_one_key_wait() {
+        // LD V0, K; LD V1, K
+        let mut chip8 = Chip8::new::<(), ()>(&|| 0xA, &|_| false);
+        chip8.load(0x200, &[0xF0, 0x0A, 0xF1, 0x0A], None);
+        chip8.set_key_with_duration(5, 3);
+        chip8.cpu_tick().unwrap();
+        chip8.cpu_tick().unwrap();
+        assert_eq!(chip8.get_regs()[..2], [5, 0xA]);
+    }
+
+    #[test]
+    fn restores_registers() {
+        let mut chip8 = chip8(&[]);
+        let mut regs = [0; 16];
+        regs[3] = 0x42;
+        regs[0xF] = 1;
+        chip8.restore_registers(regs);
+        assert_eq!(chip8.dump_registers(), regs);
+        assert_eq!(chip8.get_regs(), regs);
+    }
+
+    // Calls a routine recursing until V0 counts down to 0, then exits
+    fn recursion(depth: u8) -> Chip8 {
+        chip8(&[
+            0x60, depth, // LD V0, depth
+            0x22, 0x06, // CALL 0x206
+            0x00, 0xFD, // EXIT
+            0x70, 0xFF, // 0x206: ADD V0, -1
+            0x30, 0x00, // SE V0, 0
+            0x22, 0x06, // CALL 0x206
+            0x00, 0xEE, // RET
+        ])
+    }
+
+    #[test]
+    fn recursion_uses_all_stack_slots() {
+        let mut chip8 = recursion(16);
+        let mut max_depth = 0;
+        let result = loop {
+            if let Err(e) = chip8.cpu_tick() {
+                break e;
+            }
+            max_depth = max_depth.max(chip8.stack_depth());
+        };
+        assert_eq!(result, "Program exited");
+        assert_eq!(max_depth, 16);
+        assert_eq!(chip8.get_stack(), &[] as &[usize]);
+        assert_eq!(chip8.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn stack_overflow_is_an_error() {
+        let mut chip8 = recursion(17);
+        let result = loop {
+            if let Err(e) = chip8.cpu_tick() {
+                break e;
+            }
+        };
+        assert_eq!(result, "Stack overflow");
+        assert_eq!(chip8.stack_depth(), 16);
+    }
+
+    #[test]
+    fn stack_underflow_is_an_error() {
+        let mut chip8 = chip8(&[0x00, 0xEE]);
+        assert_eq!(chip8.cpu_tick(), Err("Stack underflow"));
+        assert_eq!(chip8.stack_depth(), 0);
+    }
+
+    #[test]
+    fn tapped_key_is_seen_by_one_check() {
+        // LD V0, 2; SKP V0; SKP V0
+        let mut chip8 = chip8(&[0x60, 0x02, 0xE0, 0x9E, 0xE0, 0x9E]);
+        chip8.cpu_tick().unwrap();
+        chip8.tap_key(2);
+        chip8.cpu_tick().unwrap();
+        assert_eq!(chip8.get_pc(), 0x206);
+
+        chip8.pc = 0x202;
+        chip8.cpu_tick().unwrap();
+        assert_eq!(chip8.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn decodes_program_into_opcodes() {
+        use disasm::Opcode::*;
+
+        let chip8 = chip8(&[
+            0x00, 0xE0, 0xF0, 0x00, 0x03, 0x00, 0xFF, 0xFF, 0xD0, 0x15, 0x12, 0x00, 0xAB,
+        ]);
+        assert_eq!(
+            chip8.decoded_program(),
+            vec![
+                (0x200, Cls),
+                (0x202, LdILong(0x300)),
+                (0x208, Drw(0, 1, 5)),
+                (0x20A, Jp(0x200)),
+            ]
+        );
+    }
+
+    #[test]
+    fn vip_timing_scales_sprite_cost_with_height() {
+        let mut chip8 = chip8(&[]);
+        assert_eq!(chip8.instruction_cost(0xD011), 1);
+        assert_eq!(chip8.instruction_cost(0xD01F), 1);
+
+        chip8.set_vip_timing(true);
+        assert_eq!(chip8.instruction_cost(0xD011), DRAW_BASE_COST + DRAW_ROW_COST);
+        assert_eq!(chip8.instruction_cost(0xD01F), DRAW_BASE_COST + 15 * DRAW_ROW_COST);
+        assert!(chip8.instruction_cost(0xD011) > chip8.instruction_cost(0x6012));
+        assert_eq!(chip8.instruction_cost(0x6012), 1);
+    }
+
+    #[cfg(feature = "safe")]
+    #[test]
+    fn every_top_nibble_is_handled() {
+        // Reaching the unreachable arm would panic under the safe feature
+        let mut chip8 = chip8(&[]);
+        for nibble in 0..16u16 {
+            chip8.pc = 0x200;
+            let _ = chip8.run_opcode(nibble << 12);
+        }
+    }
+
+    #[test]
+    fn data_at_i_round_trips() {
+        // LD I, 0x300
+        let mut chip8 = chip8(&[0xA3, 0x00]);
+        chip8.cpu_tick().unwrap();
+        chip8.set_data_at_i(&[1, 2, 3, 4]);
+        assert_eq!(chip8.data_at_i(4), &[1, 2, 3, 4]);
+        assert_eq!(chip8.get_memory(0x303), 4);
+
+        chip8.i = 4094;
+        assert_eq!(chip8.data_at_i(16).len(), 2);
+    }
+
+    #[test]
+    fn update_timers_follows_the_clock() {
+        use clock::FakeClock;
+
+        let clock = FakeClock::new();
+        clock.advance(Duration::from_secs(5));
+        // LD V0, 0xFF; LD DT, V0
+        let mut chip8 = chip8(&[0x60, 0xFF, 0xF0, 0x15]);
+        chip8.cpu_tick().unwrap();
+        chip8.cpu_tick().unwrap();
+        chip8.set_clock(Box::new(clock.clone()));
+        assert_eq!(chip8.update_timers(), 0);
+
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(chip8.update_timers(), 0);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(chip8.update_timers(), 1);
+        clock.advance(Duration::from_millis(480));
+        assert_eq!(chip8.update_timers(), 29);
+        assert_eq!(chip8.get_delay_timer(), 0xFF - 30);
+
+        // Long jumps are capped at a second's worth of ticks, and the rest is skipped
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(chip8.update_timers(), 60);
+        assert_eq!(chip8.update_timers(), 0);
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(chip8.update_timers(), 30);
+    }
+
+    #[test]
+    fn config_summary_lists_settings() {
+        let mut chip8 = chip8(&[]);
+        chip8.set_vip_timing(true);
+        chip8.display.set_blend_mode(display::BlendMode::Or);
+        assert_eq!(
+            chip8.config_summary(),
+            format!(
+                "platform: Chip-8 with SuperChip extensions\n\
+                 vip_timing: true\n\
+                 load_store_quirk: false\n\
+                 blend_mode: Or\n\
+                 safe: {}\n\
+                 memory: 4096 bytes\n",
+                cfg!(feature = "safe")
+            )
+        );
+    }
+}